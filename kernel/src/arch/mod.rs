@@ -17,6 +17,15 @@ pub fn kstart() -> ! {
     // Initialize devices
     peripheral::init_peripherals();
 
+    // Calibrate the TSC for the busy-wait delays
+    crate::utils::timer::init();
+
+    // Locate the ACPI tables (processors, local APIC, power management)
+    acpi::init();
+
+    // Seed the kernel random generator
+    crate::utils::random::init();
+
     // Check if framebuffer is available and print "hello"
     if let Some(ref mut fb) = *peripheral::FB.lock() {
         fb.puts("Visible: The framebuffer is correctly mapped.");
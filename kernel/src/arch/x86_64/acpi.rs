@@ -0,0 +1,443 @@
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+use core::slice;
+use log::{info, warn};
+use spin::Once;
+
+use syscall::io::Io;
+use syscall::pio::Pio;
+
+use crate::bootboot::bootboot;
+use crate::utils::timer;
+
+/// Maximum number of CPUs recorded from the MADT.
+pub const MAX_CPUS: usize = 64;
+
+/// Size of the standard ACPI table header.
+const SDT_HEADER_SIZE: u64 = size_of::<SdtHeader>() as u64;
+/// Offset of the first interrupt controller structure in the MADT.
+const MADT_ENTRIES_OFFSET: u64 = SDT_HEADER_SIZE + 8;
+/// MADT entry type of a processor local APIC.
+const MADT_LOCAL_APIC: u8 = 0;
+/// Length of a processor local APIC entry.
+const MADT_LOCAL_APIC_LEN: u8 = 8;
+/// MADT entry type overriding the 32-bit local APIC address.
+const MADT_LAPIC_ADDRESS_OVERRIDE: u8 = 5;
+/// Length of a local APIC address override entry.
+const MADT_LAPIC_ADDRESS_OVERRIDE_LEN: u8 = 12;
+/// Local APIC flags: the processor is enabled, or can be brought online.
+const LAPIC_ENABLED: u32 = 0b11;
+/// Offset of the 32-bit DSDT address in the FADT.
+const FADT_DSDT_OFFSET: u64 = 40;
+/// Offset of the SMI command port in the FADT.
+const FADT_SMI_COMMAND_OFFSET: u64 = 48;
+/// Offset of the value written to the SMI command port to enable ACPI mode.
+const FADT_ACPI_ENABLE_OFFSET: u64 = 52;
+/// Offset of the PM1a control block port in the FADT.
+const FADT_PM1A_CONTROL_OFFSET: u64 = 64;
+/// Offset of the PM1b control block port in the FADT.
+const FADT_PM1B_CONTROL_OFFSET: u64 = 68;
+/// Offset of the 64-bit DSDT address in the FADT, valid from ACPI 2.0.
+const FADT_X_DSDT_OFFSET: u64 = 140;
+/// AML opcode declaring a named object, e.g. `Name (_S5, ..)`.
+const AML_NAME_OP: u8 = 0x08;
+/// AML opcode starting a package.
+const AML_PACKAGE_OP: u8 = 0x12;
+/// AML opcode of the constant 0.
+const AML_ZERO_OP: u8 = 0x00;
+/// AML opcode of the constant 1.
+const AML_ONE_OP: u8 = 0x01;
+/// AML prefix of a byte constant.
+const AML_BYTE_PREFIX: u8 = 0x0A;
+/// PM1 control bit set when the machine is in ACPI mode.
+const PM1_SCI_EN: u16 = 1 << 0;
+/// Position of the sleep type field in the PM1 control registers.
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+/// Sleep type field of the PM1 control registers.
+const PM1_SLP_TYP_MASK: u16 = 0b111 << PM1_SLP_TYP_SHIFT;
+/// PM1 control bit entering the sleep state selected by the sleep type.
+const PM1_SLP_EN: u16 = 1 << 13;
+/// How long to wait for the firmware to switch to ACPI mode, in milliseconds.
+const ACPI_ENABLE_TIMEOUT_MS: u32 = 300;
+
+/// Root System Description Pointer, ACPI 2.0 layout.
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // The fields below are only valid with revision >= 2
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Header common to every System Description Table.
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// A processor found in the MADT.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LocalApic {
+    /// ACPI processor UID.
+    pub processor_id: u8,
+    /// Local APIC id of the processor.
+    pub apic_id: u8,
+}
+
+/// Information collected from the ACPI tables.
+#[derive(Debug, Copy, Clone)]
+pub struct AcpiInfo {
+    /// Physical address of the local APIC registers.
+    pub lapic_base: u64,
+    /// Usable processors, only the first `cpu_count` entries are valid.
+    pub cpus: [LocalApic; MAX_CPUS],
+    /// Number of usable processors.
+    pub cpu_count: usize,
+    /// I/O port of the PM1a control register, used by `enter_s5` to power off.
+    pub pm1a_control: u32,
+    /// I/O port of the PM1b control register, 0 if not present.
+    pub pm1b_control: u32,
+    /// I/O port taking `acpi_enable` to switch to ACPI mode, 0 if the machine is always in it.
+    pub smi_command: u32,
+    /// Value written to `smi_command` to switch to ACPI mode.
+    pub acpi_enable: u8,
+    /// PM1a and PM1b sleep types of the S5 (soft off) state, from the DSDT `\_S5` object.
+    pub s5_sleep_type: Option<(u8, u8)>,
+}
+
+impl AcpiInfo {
+    /// Returns the usable processors.
+    #[allow(dead_code)]
+    pub fn cpus(&self) -> &[LocalApic] {
+        &self.cpus[..self.cpu_count]
+    }
+}
+
+/// The information parsed from the ACPI tables, set once by `init`.
+static ACPI: Once<AcpiInfo> = Once::new();
+
+/// Reads a value from physical memory.
+///
+/// # Safety
+///
+/// BOOTBOOT identity maps the low physical memory, and the address must point into it.
+#[inline]
+unsafe fn read_phys<T: Copy>(addr: u64) -> T {
+    read_unaligned(addr as *const T)
+}
+
+/// Checks that the bytes at `addr` sum up to zero, as every ACPI table must.
+fn checksum_valid(addr: u64, len: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// Checks the RSDP signature and checksum at `addr`.
+fn rsdp_valid(addr: u64) -> bool {
+    let rsdp: Rsdp = unsafe { read_phys(addr) };
+    // The ACPI 1.0 part is always checksummed, the extended part only from revision 2
+    &rsdp.signature == b"RSD PTR " && checksum_valid(addr, 20)
+        && (rsdp.revision < 2 || checksum_valid(addr, size_of::<Rsdp>()))
+}
+
+/// Scans the EBDA and the BIOS read-only area for the RSDP.
+fn scan_rsdp() -> Option<u64> {
+    let ebda = (unsafe { read_phys::<u16>(0x40E) } as u64) << 4;
+    let areas = [(ebda, ebda + 1024), (0xE0000, 0x100000)];
+
+    areas.iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .find(|&addr| rsdp_valid(addr))
+}
+
+/// Returns the address of the root table (RSDT or XSDT), and the size of its entries.
+///
+/// BOOTBOOT passes a pointer to the system table, but also accepts an RSDP there,
+/// and falls back to scanning the BIOS areas if it's missing.
+fn find_root_table() -> Result<(u64, usize), &'static str> {
+    let ptr = unsafe { bootboot.arch.x86_64.acpi_ptr };
+
+    if ptr != 0 {
+        match unsafe { read_phys::<[u8; 4]>(ptr) } {
+            [b'X', b'S', b'D', b'T'] => return Ok((ptr, 8)),
+            [b'R', b'S', b'D', b'T'] => return Ok((ptr, 4)),
+            _ => {}
+        }
+    }
+
+    let rsdp_addr = if ptr != 0 && rsdp_valid(ptr) { ptr } else { scan_rsdp().ok_or("RSDP not found")? };
+    let rsdp: Rsdp = unsafe { read_phys(rsdp_addr) };
+
+    if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        Ok((rsdp.xsdt_address, 8))
+    } else {
+        Ok((rsdp.rsdt_address as u64, 4))
+    }
+}
+
+/// Looks up a table by its signature in the root table, and validates its checksum.
+fn find_table(root: u64, entry_size: usize, signature: &[u8; 4]) -> Option<u64> {
+    let header: SdtHeader = unsafe { read_phys(root) };
+    let count = (header.length as u64).saturating_sub(SDT_HEADER_SIZE) / entry_size as u64;
+
+    (0..count)
+        .map(|i| {
+            let entry = root + SDT_HEADER_SIZE + i * entry_size as u64;
+            match entry_size {
+                8 => unsafe { read_phys::<u64>(entry) },
+                _ => (unsafe { read_phys::<u32>(entry) }) as u64,
+            }
+        })
+        .find(|&addr| {
+            let table: SdtHeader = unsafe { read_phys(addr) };
+            &table.signature == signature && checksum_valid(addr, table.length as usize)
+        })
+}
+
+/// Collects the local APIC address and the usable processors from the MADT.
+fn parse_madt(madt: u64, info: &mut AcpiInfo) {
+    let header: SdtHeader = unsafe { read_phys(madt) };
+    let end = madt + header.length as u64;
+    let mut entry = madt + MADT_ENTRIES_OFFSET;
+
+    if entry > end {
+        warn!("ACPI: MADT too short");
+        return;
+    }
+
+    info.lapic_base = unsafe { read_phys::<u32>(madt + SDT_HEADER_SIZE) } as u64;
+
+    while entry + 2 <= end {
+        let kind: u8 = unsafe { read_phys(entry) };
+        let len: u8 = unsafe { read_phys(entry + 1) };
+        // Every entry must fit in the table, so a truncated one is never read past its end
+        if len < 2 || entry + len as u64 > end {
+            warn!("ACPI: malformed MADT entry at {:#x}", entry);
+            break;
+        }
+
+        match kind {
+            MADT_LOCAL_APIC if len >= MADT_LOCAL_APIC_LEN => {
+                let flags: u32 = unsafe { read_phys(entry + 4) };
+                if flags & LAPIC_ENABLED != 0 && info.cpu_count < MAX_CPUS {
+                    info.cpus[info.cpu_count] = LocalApic {
+                        processor_id: unsafe { read_phys(entry + 2) },
+                        apic_id: unsafe { read_phys(entry + 3) },
+                    };
+                    info.cpu_count += 1;
+                }
+            }
+            MADT_LAPIC_ADDRESS_OVERRIDE if len >= MADT_LAPIC_ADDRESS_OVERRIDE_LEN => {
+                info.lapic_base = unsafe { read_phys(entry + 4) };
+            }
+            _ => {}
+        }
+
+        entry += len as u64;
+    }
+}
+
+/// Reads an AML integer constant at the start of `aml`, if it is a byte-sized one.
+fn aml_byte(aml: &[u8]) -> Option<(u8, &[u8])> {
+    match *aml.first()? {
+        AML_ZERO_OP => Some((0, &aml[1..])),
+        AML_ONE_OP => Some((1, &aml[1..])),
+        AML_BYTE_PREFIX => Some((*aml.get(1)?, aml.get(2..)?)),
+        _ => None,
+    }
+}
+
+/// Finds the `\_S5` package in the AML of the DSDT, and returns its first two elements,
+/// the PM1a and PM1b sleep types.
+///
+/// This is a byte pattern search instead of an AML interpreter, which is enough for the
+/// plain `Name (_S5, Package () { a, b, .. })` declaration every firmware uses.
+fn find_s5(aml: &[u8]) -> Option<(u8, u8)> {
+    // The name is preceded by NameOp, possibly with a root prefix in between
+    let pos = (0..aml.len().saturating_sub(3)).find(|&i| {
+        &aml[i..i + 4] == b"_S5_" && match aml[..i] {
+            [.., AML_NAME_OP] | [.., AML_NAME_OP, b'\\'] => true,
+            _ => false,
+        }
+    })?;
+
+    let package = aml.get(pos + 4..)?;
+    if *package.first()? != AML_PACKAGE_OP {
+        return None;
+    }
+
+    // The upper two bits of the PkgLength lead byte count its extra bytes,
+    // then comes the NumElements byte
+    let pkg_length_size = (*package.get(1)? >> 6) as usize + 1;
+    let elements = package.get(1 + pkg_length_size + 1..)?;
+
+    let (typ_a, rest) = aml_byte(elements)?;
+    let (typ_b, _) = aml_byte(rest)?;
+    Some((typ_a, typ_b))
+}
+
+/// Looks up the S5 sleep types in the DSDT at `dsdt`.
+fn parse_dsdt(dsdt: u64) -> Option<(u8, u8)> {
+    let header: SdtHeader = unsafe { read_phys(dsdt) };
+    if &header.signature != b"DSDT" || (header.length as u64) < SDT_HEADER_SIZE
+        || !checksum_valid(dsdt, header.length as usize)
+    {
+        return None;
+    }
+
+    let aml = unsafe {
+        slice::from_raw_parts((dsdt + SDT_HEADER_SIZE) as *const u8, (header.length as u64 - SDT_HEADER_SIZE) as usize)
+    };
+    find_s5(aml)
+}
+
+/// Collects the power management control ports and the S5 sleep types from the FADT.
+fn parse_fadt(fadt: u64, info: &mut AcpiInfo) {
+    let header: SdtHeader = unsafe { read_phys(fadt) };
+    if (header.length as u64) < FADT_PM1B_CONTROL_OFFSET + 4 {
+        warn!("ACPI: FADT too short");
+        return;
+    }
+
+    info.smi_command = unsafe { read_phys(fadt + FADT_SMI_COMMAND_OFFSET) };
+    info.acpi_enable = unsafe { read_phys(fadt + FADT_ACPI_ENABLE_OFFSET) };
+    info.pm1a_control = unsafe { read_phys(fadt + FADT_PM1A_CONTROL_OFFSET) };
+    info.pm1b_control = unsafe { read_phys(fadt + FADT_PM1B_CONTROL_OFFSET) };
+
+    // Prefer the 64-bit DSDT address when the FADT is long enough to have one
+    let x_dsdt: u64 = if header.length as u64 >= FADT_X_DSDT_OFFSET + 8 {
+        unsafe { read_phys(fadt + FADT_X_DSDT_OFFSET) }
+    } else {
+        0
+    };
+    let dsdt = if x_dsdt != 0 { x_dsdt } else { (unsafe { read_phys::<u32>(fadt + FADT_DSDT_OFFSET) }) as u64 };
+
+    if dsdt != 0 {
+        info.s5_sleep_type = parse_dsdt(dsdt);
+    }
+    if info.s5_sleep_type.is_none() {
+        warn!("ACPI: S5 sleep type not found in the DSDT, power off is unavailable");
+    }
+}
+
+/// Locates and parses the MADT and the FADT.
+///
+/// Fails only without a valid root table. A missing or invalid MADT or FADT is reported
+/// with a warning, and the fields it would provide are left zero.
+fn parse() -> Result<AcpiInfo, &'static str> {
+    let (root, entry_size) = find_root_table()?;
+
+    let root_header: SdtHeader = unsafe { read_phys(root) };
+    if !checksum_valid(root, root_header.length as usize) {
+        return Err("invalid root table checksum");
+    }
+
+    let mut info = AcpiInfo {
+        lapic_base: 0,
+        cpus: [LocalApic::default(); MAX_CPUS],
+        cpu_count: 0,
+        pm1a_control: 0,
+        pm1b_control: 0,
+        smi_command: 0,
+        acpi_enable: 0,
+        s5_sleep_type: None,
+    };
+
+    // The tables are independent, a missing one only leaves its own fields unset
+    match find_table(root, entry_size, b"APIC") {
+        Some(madt) => parse_madt(madt, &mut info),
+        None => warn!("ACPI: MADT not found, processors unavailable"),
+    }
+    match find_table(root, entry_size, b"FACP") {
+        Some(fadt) => parse_fadt(fadt, &mut info),
+        None => warn!("ACPI: FADT not found, power management unavailable"),
+    }
+
+    Ok(info)
+}
+
+/// Parses the ACPI tables and logs the discovered processors.
+///
+/// A failure is not fatal, it only means the ACPI information is unavailable.
+pub fn init() {
+    match parse() {
+        Ok(acpi) => {
+            info!("ACPI: {} CPU(s), local APIC at {:#x}, PM1a control port {:#x}",
+                acpi.cpu_count, acpi.lapic_base, acpi.pm1a_control);
+
+            let numcores = unsafe { bootboot.numcores } as usize;
+            if acpi.cpu_count != 0 && acpi.cpu_count != numcores {
+                warn!("ACPI: CPU count differs from the {} core(s) reported by the loader", numcores);
+            }
+
+            ACPI.call_once(|| acpi);
+        }
+        Err(err) => warn!("ACPI: {}, tables unavailable", err),
+    }
+}
+
+/// Returns the information parsed from the ACPI tables, if available.
+pub fn info() -> Option<&'static AcpiInfo> {
+    ACPI.get()
+}
+
+/// Enters the S5 (soft off) sleep state, powering off the machine.
+///
+/// Switches to ACPI mode first if the firmware left the machine in legacy mode, then
+/// writes the S5 sleep type with SLP_EN to the PM1a (and PM1b) control registers.
+///
+/// # Returns
+///
+/// Only returns if the tables do not describe S5, or the machine is still running
+/// afterwards, so the caller can try another way.
+pub fn enter_s5() {
+    let acpi = match info() {
+        Some(acpi) if acpi.pm1a_control != 0 => acpi,
+        _ => return,
+    };
+    let (typ_a, typ_b) = match acpi.s5_sleep_type {
+        Some(types) => types,
+        None => return,
+    };
+
+    let mut pm1a = Pio::<u16>::new(acpi.pm1a_control as u16);
+    if pm1a.read() & PM1_SCI_EN == 0 && acpi.smi_command != 0 && acpi.acpi_enable != 0 {
+        Pio::<u8>::new(acpi.smi_command as u16).write(acpi.acpi_enable);
+        for _ in 0..ACPI_ENABLE_TIMEOUT_MS {
+            if pm1a.read() & PM1_SCI_EN != 0 {
+                break;
+            }
+            timer::delay_ms(1);
+        }
+    }
+
+    let value = (pm1a.read() & !PM1_SLP_TYP_MASK) | ((typ_a as u16) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN;
+    pm1a.write(value);
+    if acpi.pm1b_control != 0 {
+        let mut pm1b = Pio::<u16>::new(acpi.pm1b_control as u16);
+        let value = (pm1b.read() & !PM1_SLP_TYP_MASK) | ((typ_b as u16) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN;
+        pm1b.write(value);
+    }
+
+    // The power is cut asynchronously
+    timer::delay_ms(100);
+}
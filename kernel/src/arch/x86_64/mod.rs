@@ -1 +1,16 @@
-pub mod peripheral;
\ No newline at end of file
+use core::arch::x86_64::__cpuid;
+
+use crate::bootboot::bootboot;
+
+pub mod acpi;
+pub mod peripheral;
+
+/// Returns the local APIC id of the current core, as reported by CPUID.
+pub fn apic_id() -> u16 {
+    (unsafe { __cpuid(1) }.ebx >> 24) as u16
+}
+
+/// Returns true on the boot core, the one BOOTBOOT reports as `bspid`.
+pub fn is_bsp() -> bool {
+    apic_id() == unsafe { bootboot.bspid }
+}
@@ -10,15 +10,33 @@ pub struct FrameBuffer {
 }
 
 impl FrameBuffer {
+    /// Creates a new framebuffer over the screen memory provided by the loader, and clears it.
+    ///
+    /// # Arguments
+    ///
+    /// * `screen` - Pointer to the mapped screen memory.
+    /// * `scanline` - Length of a line in bytes.
+    /// * `width` - Width of the screen in pixels.
+    /// * `height` - Height of the screen in pixels.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the loader did not provide a usable framebuffer, e.g. on a headless boot.
     pub fn new(screen: *mut u32, scanline: u32, width: u32, height: u32) -> Result<FrameBuffer, &'static str> {
-        //TODO: Initialization error logic, now just emit Result
+        if screen.is_null() || width == 0 || height == 0 {
+            return Err("No framebuffer provided by the loader");
+        }
+        if (scanline as u64) < width as u64 * 4 {
+            return Err("Framebuffer scanline is shorter than its width");
+        }
+
         Ok(FrameBuffer {
             screen: unsafe {
-                let size = (scanline * height) as usize; //get the size of the framebuffer
+                let size = (scanline / 4 * height) as usize; //get the size of the framebuffer in pixels
                 write_bytes(screen, 0, size); //init self.screen
                 slice::from_raw_parts_mut(screen, size) 
             }, 
-            scanline, width, height }).map_err(|_:&'static str| "Error with Framebuffer mapping!")
+            scanline, width, height })
         }
 
 
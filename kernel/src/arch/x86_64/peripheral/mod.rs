@@ -1,12 +1,12 @@
-use core::ptr::addr_of_mut;
-use log::info;
+use core::ptr::{addr_of_mut, null_mut};
+use log::{info, warn};
 use syscall::pio::Pio;
 use spin::Mutex;
 
 use self::uart_16550::SerialPort;
 use self::framebuffer::*;
 use crate::bootboot::*;
-use crate::utils::logger;
+use crate::utils::{cmdline, logger};
 
 pub mod uart_16550;
 pub mod framebuffer;
@@ -20,14 +20,29 @@ pub static FB: Mutex<Option<FrameBuffer>> = Mutex::new(None);
 /// Initializes the peripherals.
 ///
 /// This function initializes the COM2 serial port and the framebuffer.
+/// A missing or unusable framebuffer is not fatal, the console then stays on COM2 only.
 pub fn init_peripherals() {
     COM2.lock().init();
     logger::init(true); // Init the logger engine, with clearing the screen
 
     // Now we can emit log messages
 
+    cmdline::init(); // Parse the boot options before anything else consumes them
+
+    if !cmdline::options().framebuffer {
+        info!("Framebuffer disabled with fb=off, continuing with serial console only.");
+        return;
+    }
+
+    // BOOTBOOT leaves the framebuffer pointer empty when there is no display (headless boot)
+    let screen = if unsafe { bootboot.fb_ptr }.is_null() {
+        null_mut()
+    } else {
+        (unsafe { addr_of_mut!(fb) }) as *mut u32
+    };
+
     match FrameBuffer::new(
-        unsafe { addr_of_mut!(fb) } as *mut u32,
+        screen,
         unsafe { bootboot.fb_scanline },
         unsafe { bootboot.fb_width },
         unsafe { bootboot.fb_height },
@@ -37,7 +52,7 @@ pub fn init_peripherals() {
             info!("Framebuffer mapped.");
             *FB.lock() = Some(instace)
         },
-        Err(err) => panic!("{}", err),
+        Err(err) => warn!("{}, continuing with serial console only.", err),
     }
 
     // *FB.lock() = Some(FrameBuffer::new(
@@ -44,6 +44,7 @@ extern crate log;
 
 
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU8, Ordering};
 //use alloc::string::String;
 
 use arch::kstart;
@@ -73,14 +74,58 @@ fn _start() -> ! {
 
 
 
-/// Custom panic handler that prints the error message and enters an infinite loop.
+/// Reboot after a panic instead of halting forever.
+///
+/// Halting keeps the machine state around for an attached debugger, so it is the default.
+/// Unattended runs (CI, automated test loops) want a reboot, so a crash doesn't wedge the runner.
+/// Can be overridden at boot time with the `panic=reboot|halt` option.
+const PANIC_REBOOT: bool = false;
+
+/// Time to wait after printing the panic message before rebooting, in milliseconds.
+const PANIC_REBOOT_DELAY_MS: u64 = 5000;
+
+/// Number of tracked cores, one per possible xAPIC id.
+const MAX_APIC_IDS: usize = 256;
+
+/// Initial value of a `PANIC_DEPTH` entry.
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_PANICKING: AtomicU8 = AtomicU8::new(0);
+
+/// Number of panics entered on each core, indexed by local APIC id.
+///
+/// Kept per core, so a panic raised by the panic path itself is detected, while the panics of
+/// the other cores are still reported.
+static PANIC_DEPTH: [AtomicU8; MAX_APIC_IDS] = [NOT_PANICKING; MAX_APIC_IDS];
+
+/// Custom panic handler that prints the error message, then either halts or reboots.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    serial_println!("Error: {}", info);
+    interrupts::disable();
+
+    let apic_id = arch::x86_64::apic_id();
+    let depth = PANIC_DEPTH[apic_id as usize].fetch_add(1, Ordering::SeqCst);
+
+    // The panic may have happened while this core was printing
+    unsafe { utils::writer::force_unlock_if_held() };
+
+    match depth {
+        0 => {
+            serial_println!("Error on core {}: {}", apic_id, info);
+
+            // Only the boot core reboots, a panicking AP must not reset the other cores
+            if utils::cmdline::options().panic_reboot && arch::x86_64::is_bsp() {
+                serial_println!("Rebooting in {} ms...", PANIC_REBOOT_DELAY_MS);
+                utils::power::reboot_after(PANIC_REBOOT_DELAY_MS);
+            }
+        }
+        // A panic in the panic path (e.g. the reboot) is reported, but not handled again
+        1 => serial_println!("Error on core {} while panicking: {}", apic_id, info),
+        // Printing itself panics, give up
+        _ => {}
+    }
 
     // Enter an infinite loop to halt the execution
     loop {
         hlt();
     }
 }
-
@@ -0,0 +1,140 @@
+//! Boot options passed through the BOOTBOOT environment.
+//!
+//! BOOTBOOT loads its configuration file (`bootboot_image/config`) into the environment page,
+//! as newline separated `key=value` pairs. Lines starting with `//` or `#` are comments, and
+//! so is a line starting with `/*`, up to the next `*/` (possibly several lines later).
+//! The kernel recognizes the following keys:
+//!
+//! * `loglevel=off|error|warn|info|debug|trace` - Maximum level of the emitted log messages.
+//! * `panic=halt|reboot` - Halt forever or reboot after a kernel panic.
+//! * `fb=on|off` - Use the framebuffer, or keep the console on the serial port only.
+//!
+//! Keys used by the loader itself (`screen`, `kernel`, `nosmp`) are ignored.
+
+use core::ptr::addr_of;
+use core::slice;
+use core::str::FromStr;
+use log::{debug, info, warn, LevelFilter};
+use spin::Once;
+
+use crate::bootboot::environment;
+
+/// Size of the BOOTBOOT environment page.
+const ENVIRONMENT_SIZE: usize = 4096;
+
+/// Keys interpreted by BOOTBOOT itself, which the kernel silently skips.
+const LOADER_KEYS: [&str; 3] = ["screen", "kernel", "nosmp"];
+
+/// Options the kernel was booted with.
+#[derive(Debug, Copy, Clone)]
+pub struct BootOptions {
+    /// Maximum level of the emitted log messages.
+    pub log_level: LevelFilter,
+    /// Reboot after a kernel panic instead of halting.
+    pub panic_reboot: bool,
+    /// Use the framebuffer if the loader provides one.
+    pub framebuffer: bool,
+}
+
+impl Default for BootOptions {
+    fn default() -> BootOptions {
+        BootOptions {
+            log_level: LevelFilter::Info,
+            panic_reboot: crate::PANIC_REBOOT,
+            framebuffer: true,
+        }
+    }
+}
+
+/// The parsed boot options, set once by `init`.
+static OPTIONS: Once<BootOptions> = Once::new();
+
+/// Returns the environment text passed by the loader.
+///
+/// The text is zero terminated, and at most one page long.
+fn environment_str() -> &'static str {
+    let bytes = unsafe { slice::from_raw_parts(addr_of!(environment) as *const u8, ENVIRONMENT_SIZE) };
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(ENVIRONMENT_SIZE);
+
+    match core::str::from_utf8(&bytes[..len]) {
+        Ok(text) => text,
+        // Keep everything up to the first invalid byte
+        Err(err) => unsafe { core::str::from_utf8_unchecked(&bytes[..err.valid_up_to()]) },
+    }
+}
+
+/// Parses the `key=value` lines of `text` into boot options.
+///
+/// Unknown keys and invalid values are reported with a warning and otherwise ignored.
+fn parse(text: &str) -> BootOptions {
+    let mut options = BootOptions::default();
+    let mut in_comment = false;
+
+    for line in text.lines() {
+        let mut line = line.trim();
+
+        // Skip block comments, keeping what follows their end on the same line
+        if !in_comment && line.starts_with("/*") {
+            in_comment = true;
+            line = &line[2..];
+        }
+        if in_comment {
+            match line.find("*/") {
+                Some(end) => {
+                    in_comment = false;
+                    line = line[end + 2..].trim();
+                }
+                None => continue,
+            }
+        }
+
+        if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => {
+                warn!("Boot option without value: {}", line);
+                continue;
+            }
+        };
+
+        match key {
+            "loglevel" => match LevelFilter::from_str(value) {
+                Ok(level) => options.log_level = level,
+                Err(_) => warn!("Invalid log level: {}", value),
+            },
+            "panic" => match value {
+                "halt" => options.panic_reboot = false,
+                "reboot" => options.panic_reboot = true,
+                _ => warn!("Invalid panic behaviour: {}", value),
+            },
+            "fb" => match value {
+                "on" => options.framebuffer = true,
+                "off" => options.framebuffer = false,
+                _ => warn!("Invalid framebuffer setting: {}", value),
+            },
+            _ if LOADER_KEYS.contains(&key) => {}
+            _ => warn!("Unknown boot option: {}", key),
+        }
+    }
+
+    options
+}
+
+/// Parses the boot options from the BOOTBOOT environment and applies the log level.
+///
+/// Must be called after the logger is initialized, so problems with the options can be reported.
+pub fn init() {
+    let options = OPTIONS.call_once(|| parse(environment_str()));
+
+    log::set_max_level(options.log_level);
+    info!("Boot options: {:?}", options);
+    debug!("Boot environment:\n{}", environment_str());
+}
+
+/// Returns the boot options, or the defaults if they are not parsed yet.
+pub fn options() -> BootOptions {
+    OPTIONS.get().copied().unwrap_or_default()
+}
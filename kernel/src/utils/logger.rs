@@ -1,6 +1,6 @@
 use core::fmt::Write;
 
-use log::{Record, Level, Metadata, LevelFilter};
+use log::{Record, Metadata, LevelFilter};
 
 /// Custom logger implementation for CluuLogger.
 struct CluuLogger;
@@ -8,7 +8,7 @@ struct CluuLogger;
 impl log::Log for CluuLogger {
     /// Checks if the given log level is enabled.
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+        metadata.level() <= log::max_level()
     }
 
     /// Logs the record by printing it to the console.
@@ -1,8 +1,13 @@
 pub use self::macros::*;
 pub use self::writer::*;
 pub use self::logger::*;
+pub use self::power::*;
 
 pub mod writer;
 #[macro_use]
 pub mod macros;
-pub mod logger;
\ No newline at end of file
+pub mod logger;
+pub mod cmdline;
+pub mod power;
+pub mod random;
+pub mod timer;
\ No newline at end of file
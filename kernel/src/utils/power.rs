@@ -0,0 +1,120 @@
+use core::arch::x86_64::__cpuid;
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::instructions::tables::lidt;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+use syscall::io::Io;
+use syscall::pio::Pio;
+
+use crate::arch::x86_64::acpi;
+use super::timer;
+
+/// Command port of the 8042 keyboard controller.
+const KBC_COMMAND: u16 = 0x64;
+/// Status bit of the 8042 indicating that its input buffer is still full.
+const KBC_INPUT_FULL: u8 = 1 << 1;
+/// 8042 command pulsing the CPU reset line.
+const KBC_RESET_CPU: u8 = 0xFE;
+
+/// CPUID leaf 1 ECX bit set when running under a hypervisor.
+const CPUID_HYPERVISOR: u32 = 1 << 31;
+/// CPUID leaf returning the hypervisor vendor signature.
+const CPUID_HYPERVISOR_VENDOR: u32 = 0x4000_0000;
+
+/// Hypervisor signatures, with the PM1a control ports and the values entering S5 (soft off) on them.
+///
+/// QEMU's PM base depends on the machine and firmware: 0x600 (q35, SeaBIOS) or 0xB000 (OVMF on piix4).
+const EMULATOR_POWEROFF: [(&[u8; 12], &[(u16, u16)]); 3] = [
+    (b"TCGTCGTCGTCG", &[(0x604, 0x2000), (0xB004, 0x2000)]),    // QEMU
+    (b"KVMKVMKVM\0\0\0", &[(0x604, 0x2000), (0xB004, 0x2000)]), // QEMU with KVM
+    (b"VBoxVBoxVBox", &[(0x4004, 0x3400)]),                     // VirtualBox
+];
+
+/// Returns the hypervisor vendor signature, or `None` on bare metal.
+fn hypervisor_vendor() -> Option<[u8; 12]> {
+    if unsafe { __cpuid(1) }.ecx & CPUID_HYPERVISOR == 0 {
+        return None;
+    }
+
+    let leaf = unsafe { __cpuid(CPUID_HYPERVISOR_VENDOR) };
+    let mut vendor = [0u8; 12];
+    vendor[..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&leaf.ecx.to_le_bytes());
+    vendor[8..].copy_from_slice(&leaf.edx.to_le_bytes());
+    Some(vendor)
+}
+
+/// Restarts the machine.
+///
+/// First asks the 8042 keyboard controller to pulse the reset line. If the machine is
+/// still alive afterwards, it forces a triple fault by loading an empty IDT and raising
+/// an exception.
+///
+/// # Returns
+///
+/// This function does not return.
+pub fn reboot() -> ! {
+    interrupts::disable();
+
+    let mut kbc = Pio::<u8>::new(KBC_COMMAND);
+    for _ in 0..0x10000 {
+        if kbc.read() & KBC_INPUT_FULL == 0 {
+            break;
+        }
+    }
+    kbc.write(KBC_RESET_CPU);
+    timer::delay_ms(50);
+
+    // The controller did not reset us, fall back to a triple fault
+    unsafe {
+        lidt(&DescriptorTablePointer { limit: 0, base: VirtAddr::new(0) });
+    }
+    interrupts::int3();
+
+    loop {
+        hlt();
+    }
+}
+
+/// Restarts the machine after waiting for `delay_ms` milliseconds.
+///
+/// # Arguments
+///
+/// * `delay_ms` - The time to wait before the reset, e.g. to let the serial output be read.
+///
+/// # Returns
+///
+/// This function does not return.
+pub fn reboot_after(delay_ms: u64) -> ! {
+    timer::delay_ms(delay_ms);
+    reboot()
+}
+
+/// Powers off the machine.
+///
+/// Enters the S5 (soft off) state through the PM1 control ports described by the ACPI tables.
+/// If that is unavailable or fails, and CPUID reports QEMU or VirtualBox, it writes the S5
+/// sleep command to the PM1a control port that emulator uses. Nothing is written to fixed
+/// ports on other machines, as they may belong to any device there; the machine just halts.
+///
+/// # Returns
+///
+/// This function does not return.
+#[allow(dead_code)]
+pub fn poweroff() -> ! {
+    interrupts::disable();
+
+    acpi::enter_s5();
+
+    // Still running, try the ports known from the emulators
+    if let Some(vendor) = hypervisor_vendor() {
+        let ports = EMULATOR_POWEROFF.iter().find(|(signature, _)| **signature == vendor);
+        for (port, value) in ports.map(|(_, ports)| *ports).unwrap_or(&[]) {
+            Pio::<u16>::new(*port).write(*value);
+        }
+    }
+
+    loop {
+        hlt();
+    }
+}
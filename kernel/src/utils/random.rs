@@ -0,0 +1,211 @@
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdseed64_step, _rdtsc};
+use log::{info, warn};
+use spin::Mutex;
+use x86_64::instructions::random::RdRand;
+use syscall::io::Io;
+use syscall::pio::Pio;
+
+/// "expand 32-byte k", the ChaCha constants.
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646E, 0x7962_2D32, 0x6B20_6574];
+/// Size of one ChaCha20 output block in bytes.
+const BLOCK_SIZE: usize = 64;
+/// Number of TSC samples taken when no hardware entropy source is available.
+const TSC_SAMPLES: usize = 256;
+/// Size of the buffers generated by the boot-time self-check.
+const SELF_CHECK_SIZE: usize = 32;
+
+/// The source the random generator was seeded from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EntropySource {
+    /// The RDSEED instruction (hardware entropy conditioner).
+    RdSeed,
+    /// The RDRAND instruction (hardware DRBG).
+    RdRand,
+    /// Jitter of the timestamp counter around port I/O.
+    ///
+    /// This is NOT cryptographically strong, it only makes the output differ between boots.
+    Tsc,
+}
+
+/// ChaCha20 based random number generator.
+struct ChaCha20Rng {
+    key: [u32; 8],
+    counter: u64,
+    buffer: [u8; BLOCK_SIZE],
+    index: usize,
+    source: EntropySource,
+    rdrand: Option<RdRand>,
+}
+
+impl ChaCha20Rng {
+    /// Creates a new generator, seeded from the best available entropy source.
+    fn new() -> ChaCha20Rng {
+        let rdrand = RdRand::new();
+        let mut seed = [0u64; 4];
+
+        let source = if rdseed_available() && seed.iter_mut().all(|word| rdseed(word)) {
+            EntropySource::RdSeed
+        } else if let Some(Some(words)) = rdrand.map(|rng| rdrand_words(&rng)) {
+            seed = words;
+            EntropySource::RdRand
+        } else {
+            seed = tsc_seed();
+            EntropySource::Tsc
+        };
+
+        let mut key = [0u32; 8];
+        for (i, word) in seed.iter().enumerate() {
+            key[2 * i] = *word as u32;
+            key[2 * i + 1] = (*word >> 32) as u32;
+        }
+
+        ChaCha20Rng {
+            key,
+            counter: 0,
+            buffer: [0; BLOCK_SIZE],
+            index: BLOCK_SIZE,
+            source,
+            rdrand,
+        }
+    }
+
+    /// Fills `dest` with random bytes.
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // Keep stirring in hardware randomness when we have it
+        if let Some(value) = self.rdrand.and_then(|rng| rng.get_u64()) {
+            self.key[0] ^= value as u32;
+            self.key[1] ^= (value >> 32) as u32;
+            self.index = BLOCK_SIZE;
+        }
+
+        for byte in dest.iter_mut() {
+            if self.index == BLOCK_SIZE {
+                self.refill();
+            }
+            *byte = self.buffer[self.index];
+            self.index += 1;
+        }
+    }
+
+    /// Generates the next keystream block into the buffer.
+    fn refill(&mut self) {
+        let mut state = [0u32; 16];
+        state[..4].copy_from_slice(&CHACHA_CONSTANTS);
+        state[4..12].copy_from_slice(&self.key);
+        state[12] = self.counter as u32;
+        state[13] = (self.counter >> 32) as u32;
+
+        let mut x = state;
+        for _ in 0..10 {
+            quarter_round(&mut x, 0, 4, 8, 12);
+            quarter_round(&mut x, 1, 5, 9, 13);
+            quarter_round(&mut x, 2, 6, 10, 14);
+            quarter_round(&mut x, 3, 7, 11, 15);
+            quarter_round(&mut x, 0, 5, 10, 15);
+            quarter_round(&mut x, 1, 6, 11, 12);
+            quarter_round(&mut x, 2, 7, 8, 13);
+            quarter_round(&mut x, 3, 4, 9, 14);
+        }
+
+        for (i, word) in x.iter().enumerate() {
+            let bytes = word.wrapping_add(state[i]).to_le_bytes();
+            self.buffer[i * 4..i * 4 + 4].copy_from_slice(&bytes);
+        }
+
+        self.counter = self.counter.wrapping_add(1);
+        self.index = 0;
+    }
+}
+
+/// The ChaCha quarter round on four words of the state.
+#[inline(always)]
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// Checks CPUID for RDSEED support (leaf 7, EBX bit 18).
+fn rdseed_available() -> bool {
+    unsafe { __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ebx & (1 << 18) != 0 }
+}
+
+/// Reads a word with RDSEED, retrying a few times as the instruction may underflow.
+fn rdseed(word: &mut u64) -> bool {
+    (0..10).any(|_| unsafe { _rdseed64_step(word) } == 1)
+}
+
+/// Reads four words with RDRAND.
+fn rdrand_words(rng: &RdRand) -> Option<[u64; 4]> {
+    Some([rng.get_u64()?, rng.get_u64()?, rng.get_u64()?, rng.get_u64()?])
+}
+
+/// Collects a seed from the timing jitter of port I/O, measured with the TSC.
+fn tsc_seed() -> [u64; 4] {
+    let mut port = Pio::<u8>::new(0x80);
+    let mut seed = [0u64; 4];
+    for i in 0..TSC_SAMPLES {
+        let start = unsafe { _rdtsc() };
+        port.write(0);
+        let delta = unsafe { _rdtsc() }.wrapping_sub(start);
+        // Spread the samples over the words with a splitmix64 style mix
+        let word = &mut seed[i % 4];
+        *word = (*word ^ delta ^ start).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        *word = (*word ^ (*word >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        *word = (*word ^ (*word >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        *word ^= *word >> 31;
+    }
+    seed
+}
+
+/// Mutex-protected kernel random generator, seeded on first use.
+static RNG: Mutex<Option<ChaCha20Rng>> = Mutex::new(None);
+
+/// Seeds the kernel random generator and logs the entropy source used.
+///
+/// Also runs a quick self-check: two consecutive outputs must be non-zero and differ.
+pub fn init() {
+    let source = {
+        let mut rng = RNG.lock();
+        rng.get_or_insert_with(ChaCha20Rng::new).source
+    };
+
+    match source {
+        EntropySource::Tsc => warn!("Random generator seeded from TSC jitter, it is not cryptographically strong!"),
+        _ => info!("Random generator seeded from {:?}.", source),
+    }
+
+    let mut first = [0u8; SELF_CHECK_SIZE];
+    let mut second = [0u8; SELF_CHECK_SIZE];
+    fill_bytes(&mut first);
+    fill_bytes(&mut second);
+
+    let all_zero = |buf: &[u8]| buf.iter().all(|&b| b == 0);
+    if all_zero(&first) || all_zero(&second) {
+        warn!("Random generator self-check failed: output is all zeros");
+    } else if first == second {
+        warn!("Random generator self-check failed: consecutive outputs are identical");
+    }
+}
+
+/// Fills the buffer with random bytes.
+///
+/// # Arguments
+///
+/// * `dest` - The buffer to fill.
+pub fn fill_bytes(dest: &mut [u8]) {
+    RNG.lock().get_or_insert_with(ChaCha20Rng::new).fill_bytes(dest)
+}
+
+/// Returns a random 64-bit value.
+#[allow(dead_code)]
+pub fn next_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
@@ -0,0 +1,176 @@
+use core::arch::x86_64::_rdtsc;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU64, Ordering};
+use log::{info, warn};
+use spin::Once;
+use syscall::io::Io;
+use syscall::pio::Pio;
+
+/// Input clock of the PIT in Hz.
+const PIT_FREQUENCY: u64 = 1_193_182;
+/// Length of the calibration window in milliseconds.
+const CALIBRATION_MS: u64 = 10;
+/// PIT channel 2 data port.
+const PIT_CHANNEL2: u16 = 0x42;
+/// PIT mode/command port.
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 2 gate and PC speaker control port.
+const PIT_GATE: u16 = 0x61;
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary.
+const PIT_CH2_ONESHOT: u8 = 0b1011_0000;
+/// Gate bit of channel 2.
+const GATE_ENABLE: u8 = 1 << 0;
+/// Speaker data bit, kept off during calibration.
+const SPEAKER_ENABLE: u8 = 1 << 1;
+/// Output of channel 2, set when the count reaches zero.
+const CH2_OUTPUT: u8 = 1 << 5;
+/// Channel 2 counter latch command.
+const PIT_CH2_LATCH: u8 = 0b1000_0000;
+/// Largest PIT count, about 55 ms, the window of the delay self-check.
+const PIT_MAX_COUNT: u64 = 0xFFFF;
+/// Tolerated error of the delay self-check, in percent.
+const SELF_CHECK_TOLERANCE: u64 = 5;
+/// Upper bound of gate polls while calibrating, in case there is no working PIT.
+const CALIBRATION_MAX_POLLS: u32 = 10_000_000;
+/// Unused POST diagnostic port, writing to it takes roughly 1 µs.
+const IO_DELAY_PORT: u16 = 0x80;
+
+/// TSC ticks per millisecond, 0 until calibrated.
+static TSC_KHZ: AtomicU64 = AtomicU64::new(0);
+/// Completed once the TSC is calibrated, so the PIT is only programmed by the first `init`.
+static CALIBRATED: Once<()> = Once::new();
+
+/// Reads the timestamp counter.
+#[inline(always)]
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Measures the TSC ticks elapsing during a PIT channel 2 one-shot of `CALIBRATION_MS`.
+///
+/// # Returns
+///
+/// The TSC frequency in kHz, or `None` if the PIT never reached its terminal count.
+fn calibrate() -> Option<u64> {
+    let mut gate = Pio::<u8>::new(PIT_GATE);
+    let mut command = Pio::<u8>::new(PIT_COMMAND);
+    let mut channel2 = Pio::<u8>::new(PIT_CHANNEL2);
+    let count = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+
+    // Enable the gate of channel 2, with the speaker disconnected
+    let saved = gate.read();
+    gate.write((saved & !SPEAKER_ENABLE) | GATE_ENABLE);
+
+    command.write(PIT_CH2_ONESHOT);
+    channel2.write(count as u8);
+    channel2.write((count >> 8) as u8);
+
+    let start = rdtsc();
+    let mut polls = 0;
+    while gate.read() & CH2_OUTPUT == 0 {
+        polls += 1;
+        if polls == CALIBRATION_MAX_POLLS {
+            gate.write(saved);
+            return None;
+        }
+    }
+    let end = rdtsc();
+
+    gate.write(saved);
+    Some((end - start) / CALIBRATION_MS)
+}
+
+/// Measures a `delay_ms(CALIBRATION_MS)` with PIT channel 2, counting down from its maximum.
+///
+/// # Returns
+///
+/// The elapsed time in microseconds.
+fn measure_delay() -> u64 {
+    let mut gate = Pio::<u8>::new(PIT_GATE);
+    let mut command = Pio::<u8>::new(PIT_COMMAND);
+    let mut channel2 = Pio::<u8>::new(PIT_CHANNEL2);
+
+    let saved = gate.read();
+    gate.write((saved & !SPEAKER_ENABLE) | GATE_ENABLE);
+
+    command.write(PIT_CH2_ONESHOT);
+    channel2.write(PIT_MAX_COUNT as u8);
+    channel2.write((PIT_MAX_COUNT >> 8) as u8);
+
+    delay_ms(CALIBRATION_MS);
+
+    command.write(PIT_CH2_LATCH);
+    let low = channel2.read() as u64;
+    let high = channel2.read() as u64;
+    gate.write(saved);
+
+    (PIT_MAX_COUNT - ((high << 8) | low)) * 1_000_000 / PIT_FREQUENCY
+}
+
+/// Calibrates the TSC against the PIT, to make `delay_us` and `delay_ms` accurate.
+///
+/// The result is accurate to about 1%, assuming an invariant TSC (constant rate regardless
+/// of frequency scaling), which every CPU QEMU emulates and most recent hardware provides.
+///
+/// Only the first call calibrates, later calls keep its result. This makes `init` idempotent,
+/// it does not coordinate cores: the APs currently never get this far (see `logger::init`).
+pub fn init() {
+    CALIBRATED.call_once(|| match calibrate() {
+        Some(khz) if khz > 0 => {
+            TSC_KHZ.store(khz, Ordering::Relaxed);
+            info!("TSC calibrated: {}.{:03} MHz", khz / 1000, khz % 1000);
+
+            // Check the result against the PIT, a bad calibration makes every delay wrong
+            let expected = CALIBRATION_MS * 1000;
+            let elapsed = measure_delay();
+            if elapsed.abs_diff(expected) > expected * SELF_CHECK_TOLERANCE / 100 {
+                warn!("TSC self-check: {} ms delay took {} us", CALIBRATION_MS, elapsed);
+            } else {
+                info!("TSC self-check: {} ms delay took {} us", CALIBRATION_MS, elapsed);
+            }
+        }
+        _ => warn!("TSC calibration failed, delays will be approximate"),
+    });
+}
+
+/// Returns the TSC frequency in kHz, or 0 if it is not calibrated.
+#[allow(dead_code)]
+pub fn tsc_khz() -> u64 {
+    TSC_KHZ.load(Ordering::Relaxed)
+}
+
+/// Busy-waits for `us` microseconds.
+///
+/// Works without interrupts and before the scheduler is up, so early init code and drivers
+/// can use it. Before (or without) calibration, it falls back to port I/O timing, which is
+/// only a rough estimate.
+///
+/// # Arguments
+///
+/// * `us` - The time to wait, in microseconds.
+pub fn delay_us(us: u64) {
+    let khz = TSC_KHZ.load(Ordering::Relaxed);
+
+    if khz == 0 {
+        let mut port = Pio::<u8>::new(IO_DELAY_PORT);
+        for _ in 0..us {
+            port.write(0);
+        }
+        return;
+    }
+
+    let start = rdtsc();
+    let ticks = us.saturating_mul(khz) / 1000;
+    while rdtsc().wrapping_sub(start) < ticks {
+        spin_loop();
+    }
+}
+
+/// Busy-waits for `ms` milliseconds.
+///
+/// # Arguments
+///
+/// * `ms` - The time to wait, in milliseconds.
+pub fn delay_ms(ms: u64) {
+    delay_us(ms.saturating_mul(1000));
+}
@@ -1,9 +1,17 @@
 use core::fmt;
+use core::sync::atomic::{AtomicU16, Ordering};
 use spin::MutexGuard;
 use syscall::pio::Pio;
+use arch::x86_64::apic_id;
 use arch::x86_64::peripheral::uart_16550::SerialPort;
 use arch::x86_64::peripheral::COM2;
 
+/// Value of `HOLDER` while no `Writer` holds the serial port.
+const NO_HOLDER: u16 = u16::MAX;
+
+/// Local APIC id of the core holding the serial port through a `Writer`.
+static HOLDER: AtomicU16 = AtomicU16::new(NO_HOLDER);
+
 /// A simple writer that writes to the serial port.
 pub struct Writer<'a> {
     serial: MutexGuard<'a, SerialPort<Pio<u8>>>,
@@ -18,9 +26,9 @@ impl<'a> Writer<'a> {
     /// let writer = Writer::new();
     /// ```
     pub fn new() -> Writer<'a> {
-        Writer {
-            serial: COM2.lock(),
-        }
+        let serial = COM2.lock();
+        HOLDER.store(apic_id(), Ordering::SeqCst);
+        Writer { serial }
     }
 
     /// Writes a byte to the serial port.
@@ -42,6 +50,28 @@ impl<'a> Writer<'a> {
     }
 }
 
+impl<'a> Drop for Writer<'a> {
+    /// Clears the holder, before the guard releases the serial port.
+    fn drop(&mut self) {
+        HOLDER.store(NO_HOLDER, Ordering::SeqCst);
+    }
+}
+
+/// Releases the serial port if a `Writer` of the current core holds it.
+///
+/// Used by the panic handler, as the panic may have happened while printing. A port held
+/// by another core is left alone, that core releases it when it is done.
+///
+/// # Safety
+///
+/// The `Writer` held by the current core must not be used afterwards.
+pub unsafe fn force_unlock_if_held() {
+    if HOLDER.load(Ordering::SeqCst) == apic_id() {
+        HOLDER.store(NO_HOLDER, Ordering::SeqCst);
+        COM2.force_unlock();
+    }
+}
+
 impl<'a> fmt::Write for Writer<'a> {
     /// Writes a string to the serial port.
     ///